@@ -2,13 +2,40 @@
 
 #![warn(missing_docs)]
 
-use nom::bytes::complete::{take_till, take_till1};
-use nom::character::complete::{alpha1, char, digit1, i64, space1, u64};
-use nom::combinator::{fail, map_res, opt};
-use nom::multi::many0_count;
+use std::cell::Cell;
+
 use nom::IResult;
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take_till, take_till1, take_while_m_n, take_while1};
+use nom::character::complete::{alpha1, char, digit1, newline, one_of, space1, u64};
+use nom::combinator::{fail, map, map_res, opt};
+use nom::multi::{many0, many0_count, many1, separated_list0, separated_list1};
+use nom::sequence::preceded;
 use time::{Date, Month};
 
+/// Mutable state threaded through the parsing of a single journal, used to
+/// remember what earlier directives (like `Y`) implied for lines that come
+/// after them.
+#[derive(Debug, Default)]
+pub struct ParserContext {
+    default_year: Cell<Option<i32>>,
+}
+
+impl ParserContext {
+    /// Construct a fresh context with no directives yet applied.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn default_year(&self) -> Option<i32> {
+        self.default_year.get()
+    }
+
+    fn set_default_year(&self, year: i32) {
+        self.default_year.set(Some(year));
+    }
+}
+
 /// A standalone block of text can represent a number of things.
 pub enum Block {
     /// A proper entry.
@@ -17,12 +44,139 @@ pub enum Block {
     Price(Price),
     /// Some comment line.
     Comment(String),
+    /// An `account` directive.
+    Account(Account),
+    /// An `alias` directive.
+    Alias(Alias),
+    /// A `commodity` directive.
+    Commodity(Commodity),
+    /// A `D` default commodity directive.
+    DefaultCommodity(DefaultCommodity),
+    /// A `Y` default year directive.
+    DefaultYear(DefaultYear),
+}
+
+/// Declares that an account exists, as in `account expenses:food`. Mostly
+/// used so that typos in postings can be caught ahead of time.
+pub struct Account {
+    /// The full account name, e.g. `expenses:food`.
+    pub name: String,
+}
+
+impl Account {
+    fn parse(i: &str) -> IResult<&str, Account> {
+        let (i, _) = tag("account")(i)?;
+        let (i, _) = space1(i)?;
+        let (i, name) = take_till1(|c| c == '\n')(i)?;
+
+        let account = Account {
+            name: name.trim_end().to_string(),
+        };
+        Ok((i, account))
+    }
+}
+
+/// Maps a short name to a full account path, as in
+/// `alias abbr=full:account:path`.
+pub struct Alias {
+    /// The short name used in postings.
+    pub abbreviation: String,
+    /// The full account path it expands to.
+    pub full: String,
+}
+
+impl Alias {
+    fn parse(i: &str) -> IResult<&str, Alias> {
+        let (i, _) = tag("alias")(i)?;
+        let (i, _) = space1(i)?;
+        let (i, abbreviation) = take_till1(|c| c == '=')(i)?;
+        let (i, _) = char('=')(i)?;
+        let (i, full) = take_till1(|c| c == '\n')(i)?;
+
+        let alias = Alias {
+            abbreviation: abbreviation.to_string(),
+            full: full.trim_end().to_string(),
+        };
+        Ok((i, alias))
+    }
+}
+
+/// Declares a commodity and the display style of its amounts, as in
+/// `commodity $1,000.00`.
+pub struct Commodity {
+    /// The example-formatted amount used to declare the commodity's display
+    /// style.
+    pub example: String,
+}
+
+impl Commodity {
+    fn parse(i: &str) -> IResult<&str, Commodity> {
+        let (i, _) = tag("commodity")(i)?;
+        let (i, _) = space1(i)?;
+        let (i, example) = take_till1(|c| c == '\n')(i)?;
+
+        let commodity = Commodity {
+            example: example.trim_end().to_string(),
+        };
+        Ok((i, commodity))
+    }
+}
+
+/// A default commodity and amount style directive, as in `D $1000.00`.
+///
+/// Only the example amount is recorded; amounts parsed after it are not
+/// given its commodity or style.
+pub struct DefaultCommodity {
+    /// The example-formatted amount used to declare the default display
+    /// style.
+    pub example: String,
+}
+
+impl DefaultCommodity {
+    fn parse(i: &str) -> IResult<&str, DefaultCommodity> {
+        let (i, _) = char('D')(i)?;
+        let (i, _) = space1(i)?;
+        let (i, example) = take_till1(|c| c == '\n')(i)?;
+
+        let default_commodity = DefaultCommodity {
+            example: example.trim_end().to_string(),
+        };
+        Ok((i, default_commodity))
+    }
+}
+
+/// Sets a default year, so that subsequent dates may omit theirs, as in
+/// `Y 2023`.
+pub struct DefaultYear {
+    /// The year to assume for dates that don't specify one.
+    pub year: i32,
+}
+
+impl DefaultYear {
+    fn parse<'a>(ctx: &ParserContext, i: &'a str) -> IResult<&'a str, DefaultYear> {
+        let (i, _) = char('Y')(i)?;
+        let (i, _) = space1(i)?;
+        let (i, year) = map_res(digit1, str::parse)(i)?;
+
+        ctx.set_default_year(year);
+
+        let default_year = DefaultYear { year };
+        Ok((i, default_year))
+    }
 }
 
 /// Represents the flow of some funds between one or more accounts.
 pub struct Entry {
     /// The date of the transaction.
     pub date: Date,
+    /// A potential secondary date, parsed from `=EDATE` immediately
+    /// following the primary date. Ledger calls this the "effective date".
+    pub secondary_date: Option<Date>,
+    /// The reconciliation status of the transaction.
+    pub status: Status,
+    /// A potential transaction code, parsed from a parenthesized token like
+    /// `(#1234)` appearing before the description.
+    pub code: Option<String>,
     /// A short description appearing on the same line as the date.
     pub description: String,
     /// A potential comment after the description.
@@ -31,6 +185,183 @@ pub struct Entry {
     pub lines: Vec<LineOrComment>,
 }
 
+/// The reconciliation status of an [`Entry`], parsed from an optional marker
+/// immediately following its date.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Status {
+    /// No status marker was given.
+    Unmarked,
+    /// Marked with `!`, meaning the transaction still needs review.
+    Pending,
+    /// Marked with `*`, meaning the transaction has cleared.
+    Cleared,
+}
+
+impl Status {
+    fn parse(i: &str) -> IResult<&str, Status> {
+        match opt(one_of("*!"))(i)? {
+            (i, Some('*')) => {
+                let (i, _) = space1(i)?;
+                Ok((i, Status::Cleared))
+            }
+            (i, Some('!')) => {
+                let (i, _) = space1(i)?;
+                Ok((i, Status::Pending))
+            }
+            (i, _) => Ok((i, Status::Unmarked)),
+        }
+    }
+}
+
+impl Entry {
+    fn parse<'a>(ctx: &ParserContext, i: &'a str) -> IResult<&'a str, Entry> {
+        let (i, date) = parse_date(ctx, i)?;
+        let (i, secondary_date) = opt(|i| Entry::parse_secondary_date(ctx, i))(i)?;
+        let (i, _) = space1(i)?;
+        let (i, status) = Status::parse(i)?;
+        let (i, code) = opt(Entry::parse_code)(i)?;
+        let (i, description) = Entry::parse_description(i)?;
+        let (i, comment) = opt(Entry::parse_comment)(i)?;
+        let (i, lines) = Entry::parse_lines(i)?;
+
+        let entry = Entry {
+            date,
+            secondary_date,
+            status,
+            code,
+            description,
+            comment,
+            lines,
+        };
+        Ok((i, entry))
+    }
+
+    fn parse_secondary_date<'a>(ctx: &ParserContext, i: &'a str) -> IResult<&'a str, Date> {
+        let (i, _) = char('=')(i)?;
+        parse_date(ctx, i)
+    }
+
+    fn parse_code(i: &str) -> IResult<&str, String> {
+        let (i, _) = char('(')(i)?;
+        let (i, code) = take_till1(|c| c == ')')(i)?;
+        let (i, _) = char(')')(i)?;
+
+        Ok((i, code.to_string()))
+    }
+
+    fn parse_description(i: &str) -> IResult<&str, String> {
+        let (i, _) = opt(space1)(i)?;
+        let (i, description) = take_till(|c| c == '\n' || c == ';')(i)?;
+
+        Ok((i, description.trim_end().to_string()))
+    }
+
+    fn parse_comment(i: &str) -> IResult<&str, String> {
+        let (i, _) = opt(space1)(i)?;
+        parse_comment(i)
+    }
+
+    fn parse_lines(i: &str) -> IResult<&str, Vec<LineOrComment>> {
+        let (i, _) = newline(i)?;
+        separated_list1(newline, Entry::parse_posting_line)(i)
+    }
+
+    fn parse_posting_line(i: &str) -> IResult<&str, LineOrComment> {
+        let (i, _) = space1(i)?;
+
+        alt((
+            map(parse_comment, LineOrComment::Comment),
+            map(Line::parse, LineOrComment::Line),
+        ))(i)
+    }
+
+    /// Checks that this entry's postings sum to zero per commodity, filling
+    /// in the amount of a single posting that was left blank so that it
+    /// does.
+    ///
+    /// [`PostingType::Real`] and [`PostingType::BalancedVirtual`] postings
+    /// are balanced as two separate groups, matching their documented
+    /// semantics: balanced-virtual postings must sum to zero only among
+    /// themselves, not against the real ones. [`PostingType::Virtual`]
+    /// postings never participate in balancing at all.
+    ///
+    /// Fails if either group has more than one posting missing an amount,
+    /// or if that group's commodities don't sum to exactly zero once
+    /// inference is applied.
+    pub fn balance(&mut self) -> Result<(), String> {
+        self.balance_group(PostingType::Real)?;
+        self.balance_group(PostingType::BalancedVirtual)?;
+        Ok(())
+    }
+
+    /// Balances just the [`Line`]s of the given [`PostingType`], ignoring
+    /// all others.
+    fn balance_group(&mut self, posting_type: PostingType) -> Result<(), String> {
+        let mut missing: Option<usize> = None;
+        let mut sums: Vec<(Option<String>, Option<CommoditySide>, Number)> = Vec::new();
+
+        for (i, loc) in self.lines.iter().enumerate() {
+            let line = match loc {
+                LineOrComment::Line(line) if line.posting_type == posting_type => line,
+                _ => continue,
+            };
+
+            match &line.value {
+                None if missing.is_some() => {
+                    return Err("a transaction may only omit one posting's amount".to_string());
+                }
+                None => missing = Some(i),
+                Some(vae) => match sums.iter_mut().find(|(c, _, _)| *c == vae.value.currency) {
+                    Some((_, _, total)) => *total = total.add(&vae.value.value),
+                    None => sums.push((
+                        vae.value.currency.clone(),
+                        vae.value.commodity_side,
+                        vae.value.value,
+                    )),
+                },
+            }
+        }
+
+        match missing {
+            Some(i) => {
+                let unbalanced: Vec<_> =
+                    sums.iter().filter(|(_, _, t)| !t.is_near_zero()).collect();
+
+                let inferred = match unbalanced.as_slice() {
+                    [] => Value {
+                        value: Number::Int(0, None),
+                        currency: None,
+                        commodity_side: None,
+                    },
+                    [(currency, side, total)] => Value {
+                        value: total.negate(),
+                        currency: currency.clone(),
+                        commodity_side: *side,
+                    },
+                    _ => {
+                        return Err(
+                            "cannot infer one amount across several unbalanced commodities"
+                                .to_string(),
+                        );
+                    }
+                };
+
+                if let LineOrComment::Line(line) = &mut self.lines[i] {
+                    line.value = Some(ValueAndExchange {
+                        symbol: None,
+                        value: inferred,
+                        exchange: None,
+                    });
+                }
+
+                Ok(())
+            }
+            None if sums.iter().all(|(_, _, t)| t.is_near_zero()) => Ok(()),
+            None => Err("transaction does not balance".to_string()),
+        }
+    }
+}
+
 /// A line of an [`Entry`] may be a comment or a proper [`Line`].
 pub enum LineOrComment {
     /// A proper [`Line`] of an [`Entry`].
@@ -43,30 +374,71 @@ pub enum LineOrComment {
 pub struct Line {
     /// Something like `expenses:food`.
     pub account: String,
+    /// Whether this posting is to a real account, or some kind of virtual one.
+    pub posting_type: PostingType,
     /// The monetary value.
     pub value: Option<ValueAndExchange>,
     /// A possible comment.
     pub comment: Option<String>,
 }
 
+/// Whether a [`Line`]'s account is a real account, or a virtual one used for
+/// budgeting or tracking purposes.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PostingType {
+    /// A normal posting to a real account.
+    Real,
+    /// The account is wrapped in parentheses, e.g. `(expenses:food)`. Need
+    /// not balance against the other postings in the [`Entry`].
+    Virtual,
+    /// The account is wrapped in square brackets, e.g. `[expenses:food]`.
+    /// Unlike [`PostingType::Virtual`], this must still balance.
+    BalancedVirtual,
+}
+
 impl Line {
     fn parse(i: &str) -> IResult<&str, Line> {
-        let (i, account) = Line::parse_account(i)?;
+        let (i, (posting_type, account)) = Line::parse_account(i)?;
         let (i, value) = opt(Line::parse_value)(i)?;
         let (i, comment) = opt(Line::parse_comment)(i)?;
 
         let line = Line {
             account,
+            posting_type,
             value,
             comment,
         };
         Ok((i, line))
     }
 
-    fn parse_account(i: &str) -> IResult<&str, String> {
+    fn parse_account(i: &str) -> IResult<&str, (PostingType, String)> {
+        alt((
+            Line::parse_virtual_account,
+            Line::parse_balanced_virtual_account,
+            Line::parse_real_account,
+        ))(i)
+    }
+
+    fn parse_virtual_account(i: &str) -> IResult<&str, (PostingType, String)> {
+        let (i, _) = char('(')(i)?;
+        let (i, account) = take_till1(|c| c == ')')(i)?;
+        let (i, _) = char(')')(i)?;
+
+        Ok((i, (PostingType::Virtual, account.to_string())))
+    }
+
+    fn parse_balanced_virtual_account(i: &str) -> IResult<&str, (PostingType, String)> {
+        let (i, _) = char('[')(i)?;
+        let (i, account) = take_till1(|c| c == ']')(i)?;
+        let (i, _) = char(']')(i)?;
+
+        Ok((i, (PostingType::BalancedVirtual, account.to_string())))
+    }
+
+    fn parse_real_account(i: &str) -> IResult<&str, (PostingType, String)> {
         let (i, account) = take_till1(|c| c == ' ' || c == '\n')(i)?;
 
-        Ok((i, account.to_string()))
+        Ok((i, (PostingType::Real, account.to_string())))
     }
 
     fn parse_value(i: &str) -> IResult<&str, ValueAndExchange> {
@@ -94,7 +466,13 @@ pub struct ValueAndExchange {
 impl ValueAndExchange {
     fn parse(i: &str) -> IResult<&str, ValueAndExchange> {
         let (i, symbol) = opt(char('='))(i)?;
-        let (i, _) = space1(i)?;
+        // The account/value separator is already consumed by `Line::parse_value`.
+        // A leading `=` needs its own space before the value, but a bare value
+        // does not.
+        let (i, _) = match symbol {
+            Some(_) => space1(i)?,
+            None => (i, ""),
+        };
         let (i, value) = Value::parse(i)?;
         let (i, exchange) = opt(ValueAndExchange::parse_exchange)(i)?;
 
@@ -117,21 +495,89 @@ impl ValueAndExchange {
 pub struct Value {
     /// The actual monetary value.
     pub value: Number,
-    /// Some currency marker like `CAD` or `YEN`.
+    /// Some currency marker like `CAD`, `YEN`, or `$`.
     pub currency: Option<String>,
+    /// Whether the currency sits to the left or right of the number, e.g.
+    /// `$10` versus `10 CAD`. Only meaningful when `currency` is present.
+    pub commodity_side: Option<CommoditySide>,
+}
+
+/// Whether a [`Value`]'s commodity symbol sits before or after its number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommoditySide {
+    /// The commodity sits to the left, directly against the number, as in
+    /// `$10`.
+    Left,
+    /// The commodity sits to the right, separated by a space, as in
+    /// `10 CAD`.
+    Right,
 }
 
 impl Value {
     fn parse(i: &str) -> IResult<&str, Value> {
+        alt((Value::parse_left_commodity, Value::parse_right_commodity))(i)
+    }
+
+    /// A commodity symbol directly adjacent to its number, like `$10`,
+    /// `£5.00`, or `-$10`. A minus sign before the symbol negates the
+    /// number that follows, since `parse_symbol` itself won't consume one.
+    fn parse_left_commodity(i: &str) -> IResult<&str, Value> {
+        let (i, negative) = opt(char('-'))(i)?;
+        let (i, currency) = Value::parse_symbol(i)?;
+        let (i, value) = Number::parse(i)?;
+        let value = if negative.is_some() {
+            value.negate()
+        } else {
+            value
+        };
+
+        let value = Value {
+            value,
+            currency: Some(currency),
+            commodity_side: Some(CommoditySide::Left),
+        };
+        Ok((i, value))
+    }
+
+    /// A number possibly followed by a currency, like `10 CAD` or just
+    /// `10`.
+    fn parse_right_commodity(i: &str) -> IResult<&str, Value> {
         let (i, value) = Number::parse(i)?;
         let (i, currency) = opt(Value::parse_currency)(i)?;
+        let commodity_side = currency.as_ref().map(|_| CommoditySide::Right);
 
-        let value = Value { value, currency };
+        let value = Value {
+            value,
+            currency,
+            commodity_side,
+        };
         Ok((i, value))
     }
 
+    /// A run of non-alphanumeric, non-whitespace characters like `$` or `£`.
+    /// Excludes `-` so that a negative number isn't mistaken for a symbol.
+    fn parse_symbol(i: &str) -> IResult<&str, String> {
+        let (i, symbol) =
+            take_while1(|c: char| c != '-' && !c.is_whitespace() && !c.is_alphanumeric())(i)?;
+
+        Ok((i, symbol.to_string()))
+    }
+
     fn parse_currency(i: &str) -> IResult<&str, String> {
         let (i, _) = space1(i)?;
+        alt((Value::parse_quoted_currency, Value::parse_bare_currency))(i)
+    }
+
+    /// A multi-word commodity name like `"AAPL shares"`.
+    fn parse_quoted_currency(i: &str) -> IResult<&str, String> {
+        let (i, _) = char('"')(i)?;
+        let (i, currency) = take_till1(|c| c == '"')(i)?;
+        let (i, _) = char('"')(i)?;
+
+        Ok((i, currency.to_string()))
+    }
+
+    fn parse_bare_currency(i: &str) -> IResult<&str, String> {
         let (i, currency) = alpha1(i)?;
 
         Ok((i, currency.to_string()))
@@ -144,52 +590,194 @@ impl Value {
 /// the user do not include decimal values. If they don't, then we wouldn't want
 /// to render them with extra zeroes (etc.) during pretty-printing if they
 /// didn't start with any.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Number {
     /// An indivisible positive or negative integer.
-    Int(i64),
+    ///
+    /// The inner values are the integer itself, and the digit-grouping
+    /// style used in its integer part, if any (e.g. the `,` in `1,000`).
+    Int(i64, Option<GroupSeparator>),
     /// Any other number with decimal values.
     ///
-    /// The three inner values are:
-    /// - Signed value left of the decimal point.
+    /// The inner values are:
+    /// - Whether the value is negative. Tracked separately from the whole
+    ///   part below, since that part alone can't distinguish `-0.05` from
+    ///   `0.05` once it's zero.
+    /// - The (unsigned) value left of the decimal point.
     /// - The number of zeroes following the decimal point.
     /// - The final digits, as-is, if there are any.
-    Float(i64, usize, Option<u64>),
+    /// - The digit-grouping style used in the integer part, if any.
+    Float(bool, i64, usize, Option<u64>, Option<GroupSeparator>),
+}
+
+/// The character used to group digits by thousands within the integer part
+/// of a [`Number`], kept around so pretty-printing can round-trip it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupSeparator {
+    /// Digits were grouped with a comma, as in `1,000`.
+    Comma,
 }
 
 impl Number {
     fn parse(i: &str) -> IResult<&str, Number> {
-        let (i, int) = i64(i)?;
+        let (i, (negative, magnitude, group)) = Number::parse_integer_part(i)?;
         match opt(Number::parse_float_parts)(i)? {
-            (i, None) => Ok((i, Number::Int(int))),
-            (i, Some((zeroes, last))) => Ok((i, Number::Float(int, zeroes, last))),
+            (i, None) => {
+                let value = if negative { -magnitude } else { magnitude };
+                Ok((i, Number::Int(value, group)))
+            }
+            (i, Some((zeroes, last))) => {
+                Ok((i, Number::Float(negative, magnitude, zeroes, last, group)))
+            }
         }
     }
 
+    /// Parses the (unsigned) magnitude of the integer part, along with
+    /// whether a `-` preceded it. Kept separate so that [`Number::Float`]
+    /// can record the sign even when the magnitude is zero.
+    fn parse_integer_part(i: &str) -> IResult<&str, (bool, i64, Option<GroupSeparator>)> {
+        let (i, sign) = opt(char('-'))(i)?;
+        let (i, first) = digit1(i)?;
+        let (i, rest) = many0(preceded(
+            char(','),
+            take_while_m_n(3, 3, |c: char| c.is_ascii_digit()),
+        ))(i)?;
+
+        let group = if rest.is_empty() {
+            None
+        } else {
+            Some(GroupSeparator::Comma)
+        };
+        let digits: String = std::iter::once(first).chain(rest).collect();
+        let magnitude: i64 = digits.parse().map_err(|_| {
+            nom::Err::Error(nom::error::Error::new(i, nom::error::ErrorKind::Digit))
+        })?;
+
+        Ok((i, (sign.is_some(), magnitude, group)))
+    }
+
+    /// The maximum number of fractional digits accepted in a single amount.
+    /// Comfortably covers any ledger seen in practice, while keeping
+    /// [`Number::scaled`]'s `i128` arithmetic far enough from overflow that
+    /// two genuinely different magnitudes can never collide.
+    const MAX_DECIMAL_PLACES: usize = 18;
+
     fn parse_float_parts(i: &str) -> IResult<&str, (usize, Option<u64>)> {
         let (i, _) = char('.')(i)?;
         let (i, zeroes) = many0_count(char('0'))(i)?;
         let (i, last) = opt(u64)(i)?;
 
+        let places = zeroes + last.map(|l| l.to_string().len()).unwrap_or(0);
+        if places > Number::MAX_DECIMAL_PLACES {
+            // A `Failure`, not a recoverable `Error`: an amount with this
+            // many fractional digits was clearly intentional, so we should
+            // reject it outright rather than have the enclosing `opt`
+            // quietly reinterpret it as an integer with trailing garbage.
+            return Err(nom::Err::Failure(nom::error::Error::new(
+                i,
+                nom::error::ErrorKind::TooLarge,
+            )));
+        }
+
         Ok((i, (zeroes, last)))
     }
+
+    /// The number of decimal places implied by this value's fractional
+    /// part.
+    fn decimal_places(&self) -> usize {
+        match self {
+            Number::Int(_, _) => 0,
+            Number::Float(_, _, zeroes, last, _) => {
+                zeroes + last.map(|l| l.to_string().len()).unwrap_or(0)
+            }
+        }
+    }
+
+    /// This value as an integer, scaled up by `10.pow(places)`, losing the
+    /// group separator and trailing-zero styling in the process. `places`
+    /// must be at least `self.decimal_places()`.
+    ///
+    /// Widened to `i128` and saturating so that a syntactically valid but
+    /// absurdly long fractional tail can't overflow this into a panic.
+    fn scaled(&self, places: usize) -> i128 {
+        let scale = 10i128.checked_pow(places as u32).unwrap_or(i128::MAX);
+        match self {
+            Number::Int(i, _) => (*i as i128).saturating_mul(scale),
+            Number::Float(negative, whole, zeroes, last, _) => {
+                let digits = format!(
+                    "{:0<width$}",
+                    format!(
+                        "{}{}",
+                        "0".repeat(*zeroes),
+                        last.map(|l| l.to_string()).unwrap_or_default()
+                    ),
+                    width = places
+                );
+                let frac: i128 = if digits.is_empty() {
+                    0
+                } else {
+                    digits.parse().unwrap_or(i128::MAX)
+                };
+                let magnitude = (*whole as i128).saturating_mul(scale).saturating_add(frac);
+                if *negative { -magnitude } else { magnitude }
+            }
+        }
+    }
+
+    /// Reconstructs a [`Number`] from an integer previously produced by
+    /// [`Number::scaled`] at the given number of decimal places.
+    fn from_scaled(value: i128, places: usize) -> Number {
+        if places == 0 {
+            Number::Int(value.clamp(i64::MIN as i128, i64::MAX as i128) as i64, None)
+        } else {
+            let scale = 10i128.checked_pow(places as u32).unwrap_or(i128::MAX);
+            let negative = value.is_negative();
+            let magnitude = value.unsigned_abs();
+            let whole = (magnitude / scale.unsigned_abs()).min(i64::MAX as u128) as i64;
+            let frac = (magnitude % scale.unsigned_abs()).min(u64::MAX as u128) as u64;
+            let (zeroes, last) = if frac == 0 {
+                (places, None)
+            } else {
+                (places.saturating_sub(frac.to_string().len()), Some(frac))
+            };
+            Number::Float(negative, whole, zeroes, last, None)
+        }
+    }
+
+    /// Adds two numbers together, preserving the [`Number::Int`] variant
+    /// only when both operands are themselves integers.
+    fn add(&self, other: &Number) -> Number {
+        let places = self.decimal_places().max(other.decimal_places());
+        Number::from_scaled(
+            self.scaled(places).saturating_add(other.scaled(places)),
+            places,
+        )
+    }
+
+    /// Flips the sign of this value.
+    fn negate(&self) -> Number {
+        match self {
+            Number::Int(i, group) => Number::Int(-i, *group),
+            Number::Float(negative, whole, zeroes, last, group) => {
+                Number::Float(!negative, *whole, *zeroes, *last, *group)
+            }
+        }
+    }
+
+    /// Whether this value is close enough to zero to be considered balanced.
+    ///
+    /// [`Number`] is scaled to an exact integer before comparison, so this
+    /// is really an exact check, but it reads as "near zero" at call sites
+    /// that are really asking "does this commodity's total balance out".
+    fn is_near_zero(&self) -> bool {
+        self.scaled(self.decimal_places()) == 0
+    }
 }
 
 impl PartialEq for Number {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Number::Int(l), Number::Int(r)) => l == r,
-            (Number::Int(l), Number::Float(r, _, None)) => l == r,
-            (Number::Int(_), Number::Float(_, _, Some(_))) => false,
-            (Number::Float(l, _, None), Number::Int(r)) => l == r,
-            (Number::Float(_, _, Some(_)), Number::Int(_)) => false,
-            (Number::Float(l, _, None), Number::Float(r, _, None)) => l == r,
-            (Number::Float(_, _, Some(_)), Number::Float(_, _, None)) => false,
-            (Number::Float(_, _, None), Number::Float(_, _, Some(_))) => false,
-            (Number::Float(l, a, Some(x)), Number::Float(r, b, Some(y))) => {
-                l == r && a == b && x == y
-            }
-        }
+        let places = self.decimal_places().max(other.decimal_places());
+        self.scaled(places) == other.scaled(places)
     }
 }
 
@@ -207,7 +795,28 @@ pub enum Exchange {
 
 impl Exchange {
     fn parse(i: &str) -> IResult<&str, Exchange> {
-        todo!()
+        // Try the double-`@` total form before the single-`@` per-unit form,
+        // since the latter would otherwise match the first `@` of the former.
+        match Exchange::parse_total(i) {
+            ok @ Ok(_) => ok,
+            Err(_) => Exchange::parse_per_unit(i),
+        }
+    }
+
+    fn parse_per_unit(i: &str) -> IResult<&str, Exchange> {
+        let (i, _) = char('@')(i)?;
+        let (i, _) = space1(i)?;
+        let (i, value) = Value::parse(i)?;
+
+        Ok((i, Exchange::PerUnit(value)))
+    }
+
+    fn parse_total(i: &str) -> IResult<&str, Exchange> {
+        let (i, _) = tag("@@")(i)?;
+        let (i, _) = space1(i)?;
+        let (i, value) = Value::parse(i)?;
+
+        Ok((i, Exchange::Total(value)))
     }
 }
 
@@ -240,10 +849,10 @@ impl Price {
         }
     }
 
-    fn parse(i: &str) -> IResult<&str, Price> {
+    fn parse<'a>(ctx: &ParserContext, i: &'a str) -> IResult<&'a str, Price> {
         let (i, _) = char('P')(i)?;
         let (i, _) = space1(i)?;
-        let (i, date) = parse_date(i)?;
+        let (i, date) = parse_date(ctx, i)?;
         let (i, _) = space1(i)?;
         let (i, asset) = alpha1(i)?;
         let (i, _) = space1(i)?;
@@ -260,11 +869,39 @@ impl Price {
     }
 }
 
-fn parse_date(i: &str) -> IResult<&str, Date> {
+fn parse_date<'a>(ctx: &ParserContext, i: &'a str) -> IResult<&'a str, Date> {
+    match parse_full_date(i) {
+        ok @ Ok(_) => ok,
+        Err(_) => match ctx.default_year() {
+            Some(year) => parse_year_less_date(year, i),
+            None => fail(i),
+        },
+    }
+}
+
+/// The characters ledger journals accept as date separators: `2023-07-16`,
+/// `2023/07/16`, and `2023.07.16` are all valid, but the same separator must
+/// be used throughout a single date.
+const DATE_SEPARATORS: &str = "-/.";
+
+fn parse_full_date(i: &str) -> IResult<&str, Date> {
     let (i, year) = map_res(digit1, str::parse)(i)?;
-    let (i, _) = char('-')(i)?;
+    let (i, sep) = one_of(DATE_SEPARATORS)(i)?;
+    let (i, month) = parse_month(i)?;
+    let (i, _) = char(sep)(i)?;
+    let (i, day) = map_res(digit1, str::parse)(i)?;
+
+    match Date::from_calendar_date(year, month, day) {
+        Ok(date) => Ok((i, date)),
+        Err(_) => fail(i),
+    }
+}
+
+/// A `MM-DD` date (or `MM/DD`, `MM.DD`), valid only once a default year has
+/// been set by a `Y` directive.
+fn parse_year_less_date(year: i32, i: &str) -> IResult<&str, Date> {
     let (i, month) = parse_month(i)?;
-    let (i, _) = char('-')(i)?;
+    let (i, _) = one_of(DATE_SEPARATORS)(i)?;
     let (i, day) = map_res(digit1, str::parse)(i)?;
 
     match Date::from_calendar_date(year, month, day) {
@@ -294,12 +931,66 @@ fn parse_month(i: &str) -> IResult<&str, Month> {
 }
 
 fn parse_comment(i: &str) -> IResult<&str, String> {
-    let (i, _) = char(';')(i)?;
+    let (i, _) = one_of(";#*")(i)?;
     let (i, comment) = take_till(|c| c == '\n')(i)?;
 
     Ok((i, comment.to_string()))
 }
 
+impl Block {
+    fn parse<'a>(ctx: &ParserContext, i: &'a str) -> IResult<&'a str, Block> {
+        alt((
+            map(|i| Entry::parse(ctx, i), Block::Entry),
+            map(|i| Price::parse(ctx, i), Block::Price),
+            map(parse_comment, Block::Comment),
+            map(Account::parse, Block::Account),
+            map(Alias::parse, Block::Alias),
+            map(Commodity::parse, Block::Commodity),
+            map(DefaultCommodity::parse, Block::DefaultCommodity),
+            map(|i| DefaultYear::parse(ctx, i), Block::DefaultYear),
+        ))(i)
+    }
+}
+
+/// Parse an entire hledger journal into its constituent [`Block`]s.
+///
+/// Dispatches on the first character (or keyword) of each block the way
+/// hledger itself does: a digit starts an [`Entry`], `P` starts a
+/// [`Price`], `;`/`#`/`*` start a comment, and a directive keyword starts
+/// its respective directive. A `Y` directive found along the way updates
+/// the default year used to parse any subsequent year-less dates.
+pub fn parse_journal(input: &str) -> IResult<&str, Vec<Block>> {
+    let ctx = ParserContext::new();
+
+    let (i, _) = many0(newline)(input)?;
+    let (i, blocks) = separated_list0(many1(newline), |i| Block::parse(&ctx, i))(i)?;
+    let (i, _) = many0(newline)(i)?;
+
+    Ok((i, blocks))
+}
+
+/// Parse an entire hledger journal, reporting the first parse failure with
+/// its line and column rather than failing silently.
+pub fn parse_str(input: &str) -> Result<Vec<Block>, String> {
+    match parse_journal(input) {
+        Ok(("", blocks)) => Ok(blocks),
+        Ok((rest, _)) => Err(locate_error(input, rest)),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Err(locate_error(input, e.input)),
+        Err(nom::Err::Incomplete(_)) => Err("unexpected end of input".to_string()),
+    }
+}
+
+/// Renders a friendly "line N, column N" message for the point in `input`
+/// where `rest` begins.
+fn locate_error(input: &str, rest: &str) -> String {
+    let consumed = input.len() - rest.len();
+    let prefix = &input[..consumed];
+    let line = prefix.matches('\n').count() + 1;
+    let column = prefix.len() - prefix.rfind('\n').map(|p| p + 1).unwrap_or(0) + 1;
+
+    format!("failed to parse journal at line {line}, column {column}")
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -307,9 +998,9 @@ mod test {
     #[test]
     fn numbers() {
         let nums = [
-            (Number::Int(600), "600"),
-            (Number::Float(600, 3, None), "600.000"),
-            (Number::Float(600, 3, Some(123)), "600.000123"),
+            (Number::Int(600, None), "600"),
+            (Number::Float(false, 600, 3, None, None), "600.000"),
+            (Number::Float(false, 600, 3, Some(123), None), "600.000123"),
         ];
 
         nums.into_iter().for_each(|(exp, s)| {
@@ -318,7 +1009,44 @@ mod test {
             assert_eq!(exp, parsed);
         });
 
-        assert_eq!(Number::Int(600), Number::Float(600, 1000, None));
+        assert_eq!(
+            Number::Int(600, None),
+            Number::Float(false, 600, 1000, None, None)
+        );
+    }
+
+    #[test]
+    fn grouped_numbers() {
+        let (rem, parsed) = Number::parse("1,927.20").unwrap();
+        assert_eq!("", rem);
+        assert_eq!(Number::Float(false, 1927, 0, Some(20), None), parsed);
+        assert!(matches!(
+            parsed,
+            Number::Float(_, _, _, _, Some(GroupSeparator::Comma))
+        ));
+    }
+
+    #[test]
+    fn commodity_symbols() {
+        let (rem, parsed) = Value::parse("$10").unwrap();
+        assert_eq!("", rem);
+        assert_eq!(Some("$".to_string()), parsed.currency);
+        assert_eq!(Some(CommoditySide::Left), parsed.commodity_side);
+        assert_eq!(Number::Int(10, None), parsed.value);
+
+        let (rem, parsed) = Value::parse("1,000.00 EUR").unwrap();
+        assert_eq!("", rem);
+        assert_eq!(Some("EUR".to_string()), parsed.currency);
+        assert_eq!(Some(CommoditySide::Right), parsed.commodity_side);
+
+        let (rem, parsed) = Value::parse("10 \"AAPL shares\"").unwrap();
+        assert_eq!("", rem);
+        assert_eq!(Some("AAPL shares".to_string()), parsed.currency);
+
+        let (rem, parsed) = Value::parse("-$10").unwrap();
+        assert_eq!("", rem);
+        assert_eq!(Some("$".to_string()), parsed.currency);
+        assert_eq!(Number::Int(-10, None), parsed.value);
     }
 
     #[test]
@@ -326,21 +1054,173 @@ mod test {
         let line = "assets:cash:stash    200000 Y @@ 1927.20 C";
         let (rem, parsed) = Line::parse(line).unwrap();
         assert_eq!("", rem);
-        // assert_eq!(200000, parsed.value.unwrap().value.value);
+        assert_eq!(Number::Int(200000, None), parsed.value.unwrap().value.value);
+        assert_eq!(PostingType::Real, parsed.posting_type);
+    }
+
+    #[test]
+    fn virtual_postings() {
+        let line = "(assets:cash:envelope)  50 CAD";
+        let (rem, parsed) = Line::parse(line).unwrap();
+        assert_eq!("", rem);
+        assert_eq!(PostingType::Virtual, parsed.posting_type);
+        assert_eq!("assets:cash:envelope", parsed.account);
+
+        let line = "[assets:cash:envelope]  50 CAD";
+        let (rem, parsed) = Line::parse(line).unwrap();
+        assert_eq!("", rem);
+        assert_eq!(PostingType::BalancedVirtual, parsed.posting_type);
+        assert_eq!("assets:cash:envelope", parsed.account);
+    }
+
+    #[test]
+    fn entries() {
+        let ctx = ParserContext::new();
+        let entry =
+            "2012-01-01=2012-01-02 * (#1234) Safeway\n  expenses:food  10 CAD\n  assets:cash";
+        let (rem, parsed) = Entry::parse(&ctx, entry).unwrap();
+        assert_eq!("", rem);
+        assert_eq!(Status::Cleared, parsed.status);
+        assert_eq!(Some("#1234".to_string()), parsed.code);
+        assert_eq!("Safeway", parsed.description);
+        assert!(parsed.secondary_date.is_some());
+        assert_eq!(2, parsed.lines.len());
+    }
+
+    #[test]
+    fn balances() {
+        let ctx = ParserContext::new();
+        let entry = "2012-01-01 Safeway\n  expenses:food  10 CAD\n  assets:cash";
+        let (_, mut parsed) = Entry::parse(&ctx, entry).unwrap();
+        assert!(parsed.balance().is_ok());
+        match &parsed.lines[1] {
+            LineOrComment::Line(line) => {
+                let value = line.value.as_ref().unwrap();
+                assert_eq!(Number::Int(-10, None), value.value.value);
+                assert_eq!(Some("CAD".to_string()), value.value.currency);
+            }
+            LineOrComment::Comment(_) => panic!("expected a posting line"),
+        }
+
+        let unbalanced = "2012-01-01 Safeway\n  expenses:food  10 CAD\n  assets:cash  -5 CAD";
+        let (_, mut parsed) = Entry::parse(&ctx, unbalanced).unwrap();
+        assert!(parsed.balance().is_err());
+
+        let two_missing = "2012-01-01 Safeway\n  expenses:food\n  assets:cash";
+        let (_, mut parsed) = Entry::parse(&ctx, two_missing).unwrap();
+        assert!(parsed.balance().is_err());
+
+        // A sub-unit amount whose whole part is zero must still carry its
+        // sign, or else this (balanced) entry would be reported as not
+        // balancing.
+        let subunit = "2012-01-01 Safeway\n  expenses:food  0.05 CAD\n  assets:cash  -0.05 CAD";
+        let (_, mut parsed) = Entry::parse(&ctx, subunit).unwrap();
+        assert!(parsed.balance().is_ok());
+
+        // A long, but syntactically valid, fractional tail shouldn't panic.
+        let long_tail =
+            "2012-01-01 Safeway\n  expenses:food  1.123456789012345678 CAD\n  assets:cash";
+        let (_, mut parsed) = Entry::parse(&ctx, long_tail).unwrap();
+        assert!(parsed.balance().is_ok());
+
+        // A fractional tail past `Number::MAX_DECIMAL_PLACES` is rejected
+        // outright, rather than silently discarded (which would otherwise
+        // let two very different magnitudes collide once scaled).
+        let too_long =
+            "2012-01-01 Safeway\n  expenses:food  1.1234567890123456789 CAD\n  assets:cash";
+        assert!(Entry::parse(&ctx, too_long).is_err());
+
+        // A virtual posting need not balance against the reals.
+        let with_virtual = "2012-01-01 Safeway\n  expenses:food  10 CAD\n  assets:cash  -10 CAD\n  (assets:envelope)  10 CAD";
+        let (_, mut parsed) = Entry::parse(&ctx, with_virtual).unwrap();
+        assert!(parsed.balance().is_ok());
+
+        // Balanced-virtual postings must balance, but only among
+        // themselves, not against the reals.
+        let with_balanced_virtual = "2012-01-01 Safeway\n  expenses:food  10 CAD\n  assets:cash  -10 CAD\n  [assets:envelope]  5 CAD\n  [assets:other]  -5 CAD";
+        let (_, mut parsed) = Entry::parse(&ctx, with_balanced_virtual).unwrap();
+        assert!(parsed.balance().is_ok());
+
+        let unbalanced_balanced_virtual = "2012-01-01 Safeway\n  expenses:food  10 CAD\n  assets:cash  -10 CAD\n  [assets:envelope]  5 CAD";
+        let (_, mut parsed) = Entry::parse(&ctx, unbalanced_balanced_virtual).unwrap();
+        assert!(parsed.balance().is_err());
     }
 
     #[test]
     fn dates() {
+        let ctx = ParserContext::new();
         let date = "2022-07-16";
-        assert!(parse_date(date).is_ok());
+        assert!(parse_date(&ctx, date).is_ok());
+
+        let expected = Date::from_calendar_date(2009, Month::January, 1).unwrap();
+
+        let (rem, slashed) = parse_date(&ctx, "2009/1/1").unwrap();
+        assert_eq!("", rem);
+        assert_eq!(expected, slashed);
+
+        let (rem, dotted) = parse_date(&ctx, "2009.1.1").unwrap();
+        assert_eq!("", rem);
+        assert_eq!(expected, dotted);
+    }
+
+    #[test]
+    fn default_year_directive() {
+        let ctx = ParserContext::new();
+        let (rem, default_year) = DefaultYear::parse(&ctx, "Y 2023").unwrap();
+        assert_eq!("", rem);
+        assert_eq!(2023, default_year.year);
+        assert_eq!(Some(2023), ctx.default_year());
+
+        let (rem, date) = parse_date(&ctx, "07-16").unwrap();
+        assert_eq!("", rem);
+        assert_eq!(
+            Date::from_calendar_date(2023, Month::July, 16).unwrap(),
+            date
+        );
+    }
+
+    #[test]
+    fn accounts_and_aliases() {
+        let (rem, account) = Account::parse("account expenses:food").unwrap();
+        assert_eq!("", rem);
+        assert_eq!("expenses:food", account.name);
+
+        let (rem, alias) = Alias::parse("alias food=expenses:food").unwrap();
+        assert_eq!("", rem);
+        assert_eq!("food", alias.abbreviation);
+        assert_eq!("expenses:food", alias.full);
     }
 
     #[test]
     fn prices() {
+        let ctx = ParserContext::new();
         let price = "P 2022-07-12 TSLA 699.21 U ; great buy?";
-        let (rem, parsed) = Price::parse(price).unwrap();
+        let (rem, parsed) = Price::parse(&ctx, price).unwrap();
         assert_eq!("", rem);
         assert_eq!(parsed.asset, "TSLA");
-        assert_eq!(parsed.value.value, Number::Float(699, 0, Some(21)));
+        assert_eq!(
+            parsed.value.value,
+            Number::Float(false, 699, 0, Some(21), None)
+        );
+    }
+
+    #[test]
+    fn journals() {
+        let journal = "account expenses:food\n\nP 2022-07-12 TSLA 699.21 U\n\n2012-01-01 * Safeway\n  expenses:food  10 CAD\n  assets:cash\n";
+        let (rem, blocks) = parse_journal(journal).unwrap();
+        assert_eq!("", rem);
+        assert_eq!(3, blocks.len());
+        assert!(matches!(blocks[0], Block::Account(_)));
+        assert!(matches!(blocks[1], Block::Price(_)));
+        assert!(matches!(blocks[2], Block::Entry(_)));
+    }
+
+    #[test]
+    fn journal_parse_errors_have_a_location() {
+        let journal = "2012-01-01 * Safeway\n  expenses:food  10 CAD\n  assets:cash\n\n!!!\n";
+        match parse_str(journal) {
+            Ok(_) => panic!("expected a parse error"),
+            Err(e) => assert!(e.contains("line 5")),
+        }
     }
 }